@@ -0,0 +1,197 @@
+//! VCS backend abstraction.
+//!
+//! `run()` used to shell out to the `git` CLI directly, which meant every source had to be a
+//! git repository tracked by branch HEAD. The `Backend` trait pulls those operations out so
+//! other DVCS tools (or a plain local directory) can be plugged in, and so a source can be
+//! pinned to an exact revision instead of always following a branch.
+
+use serde::{Deserialize, Serialize};
+
+/// A submodule's path (relative to the repo root) and the commit it's currently pinned to, as
+/// reported by `git submodule status --recursive`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubmoduleStatus {
+    pub path: String,
+    pub commit: String,
+}
+
+/// Operations a translation source must support so `run()` can sync it to a local working
+/// directory and know which revision it ended up at.
+pub trait Backend {
+    /// Bring `dest` up to date with `remote`/`branch`, cloning if `dest` doesn't exist yet and
+    /// pulling otherwise.
+    fn sync(&self, remote: &str, branch: &str, dest: &str) -> Result<(), String>;
+
+    /// Return the revision currently checked out at `dest`.
+    fn current_commit(&self, dest: &str) -> Result<String, String>;
+
+    /// Check out a specific revision (commit hash or tag) at `dest`.
+    fn checkout(&self, dest: &str, rev: &str) -> Result<(), String>;
+
+    /// Initialize and update every submodule under `dest`, recursively. Safe to call
+    /// unconditionally after a clone, a pull, or a checkout: it picks up submodules that were
+    /// just added as well as ones already present.
+    fn sync_submodules(&self, dest: &str) -> Result<(), String>;
+
+    /// List the commit each submodule under `dest` is currently pinned to.
+    fn submodule_status(&self, dest: &str) -> Result<Vec<SubmoduleStatus>, String>;
+}
+
+/// `Backend` implementation that shells out to the `git` CLI, matching the behavior `run()`
+/// had before it was abstracted.
+pub struct GitBackend;
+
+impl Backend for GitBackend {
+    fn sync(&self, remote: &str, branch: &str, dest: &str) -> Result<(), String> {
+        if std::path::Path::new(dest).exists() {
+            println!("Pulling the latest changes from the repo: {}", remote);
+            // return to `branch` first: a previous run may have left a pinned revision checked
+            // out, and `git pull` fails on a detached HEAD
+            self.checkout(dest, branch)?;
+            let output = std::process::Command::new("git")
+                .arg("pull")
+                .current_dir(dest)
+                .output()
+                .map_err(|e| format!("failed to pull {}: {}", remote, e))?;
+            println!("{}", String::from_utf8_lossy(&output.stdout));
+            println!("{}", String::from_utf8_lossy(&output.stderr));
+            if !output.status.success() {
+                return Err(format!(
+                    "git pull failed: {}",
+                    String::from_utf8_lossy(&output.stderr)
+                ));
+            }
+        } else {
+            println!("Cloning the repo: {}", remote);
+            let output = std::process::Command::new("git")
+                .arg("clone")
+                .arg("--branch")
+                .arg(branch)
+                .arg(remote)
+                .arg(dest)
+                .output()
+                .map_err(|e| format!("failed to clone {}: {}", remote, e))?;
+            println!("{}", String::from_utf8_lossy(&output.stdout));
+            println!("{}", String::from_utf8_lossy(&output.stderr));
+            if !output.status.success() {
+                return Err(format!(
+                    "git clone failed: {}",
+                    String::from_utf8_lossy(&output.stderr)
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn current_commit(&self, dest: &str) -> Result<String, String> {
+        let output = std::process::Command::new("git")
+            .arg("rev-parse")
+            .arg("HEAD")
+            .current_dir(dest)
+            .output()
+            .map_err(|e| format!("failed to get the latest commit hash: {}", e))?;
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    fn checkout(&self, dest: &str, rev: &str) -> Result<(), String> {
+        let output = std::process::Command::new("git")
+            .arg("checkout")
+            .arg(rev)
+            .current_dir(dest)
+            .output()
+            .map_err(|e| format!("failed to checkout {}: {}", rev, e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "git checkout {} failed: {}",
+                rev,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(())
+    }
+
+    fn sync_submodules(&self, dest: &str) -> Result<(), String> {
+        let output = std::process::Command::new("git")
+            .arg("submodule")
+            .arg("update")
+            .arg("--init")
+            .arg("--recursive")
+            .current_dir(dest)
+            .output()
+            .map_err(|e| format!("failed to sync submodules: {}", e))?;
+        println!("{}", String::from_utf8_lossy(&output.stdout));
+        println!("{}", String::from_utf8_lossy(&output.stderr));
+        if !output.status.success() {
+            return Err(format!(
+                "git submodule update failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(())
+    }
+
+    fn submodule_status(&self, dest: &str) -> Result<Vec<SubmoduleStatus>, String> {
+        let output = std::process::Command::new("git")
+            .arg("submodule")
+            .arg("status")
+            .arg("--recursive")
+            .current_dir(dest)
+            .output()
+            .map_err(|e| format!("failed to list submodule status: {}", e))?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok(stdout.lines().filter_map(parse_submodule_status_line).collect())
+    }
+}
+
+/// Parse a single line of `git submodule status --recursive` output, which looks like
+/// " <commit> <path> (<describe>)", optionally prefixed with '-' (not initialized), '+'
+/// (checked-out commit differs from the pin) or 'U' (merge conflict). Returns `None` for a
+/// line that doesn't have at least a commit and a path.
+fn parse_submodule_status_line(line: &str) -> Option<SubmoduleStatus> {
+    let line = line.trim_start_matches(['-', '+', 'U']).trim();
+    let mut parts = line.split_whitespace();
+    let commit = parts.next()?;
+    let path = parts.next()?;
+    Some(SubmoduleStatus {
+        path: path.to_string(),
+        commit: commit.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_clean_line() {
+        let status = parse_submodule_status_line(" abc123 vendor/lib (heads/main)").unwrap();
+        assert_eq!(status.commit, "abc123");
+        assert_eq!(status.path, "vendor/lib");
+    }
+
+    #[test]
+    fn strips_not_initialized_prefix() {
+        let status = parse_submodule_status_line("-def456 vendor/uninitialized").unwrap();
+        assert_eq!(status.commit, "def456");
+        assert_eq!(status.path, "vendor/uninitialized");
+    }
+
+    #[test]
+    fn strips_ahead_of_pin_prefix() {
+        let status = parse_submodule_status_line("+789abc vendor/ahead (heads/main)").unwrap();
+        assert_eq!(status.commit, "789abc");
+        assert_eq!(status.path, "vendor/ahead");
+    }
+
+    #[test]
+    fn strips_merge_conflict_prefix() {
+        let status = parse_submodule_status_line("Ufedcba vendor/conflicted").unwrap();
+        assert_eq!(status.commit, "fedcba");
+        assert_eq!(status.path, "vendor/conflicted");
+    }
+
+    #[test]
+    fn returns_none_for_blank_line() {
+        assert!(parse_submodule_status_line("").is_none());
+    }
+}
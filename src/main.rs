@@ -6,19 +6,142 @@
 //! operations like cloning a repository, checking for changes, and ensuring that only files that have not been
 //! translated or have changed are retranslated.
 
+mod backend;
+#[cfg(feature = "rhai")]
+mod script;
+
+use backend::{Backend, GitBackend};
 use clap::{Command, arg};
-use openai_api_rust::chat::*;
-use openai_api_rust::*;
+use futures::stream::{self, StreamExt};
 use serde::{Deserialize, Serialize};
 use serde_yaml::{self};
 use sha2::Digest;
-use std::io::Write;
+use std::sync::Arc;
+
+/// What to translate a single file with: the target language, the prompt template sent to the
+/// model (`{language}` and `{content}` are substituted in), and optionally which named engine
+/// to use instead of the configured default. Produced either by a Rhai script's `decide()` or,
+/// when no script is configured, `FileDecision::default()`.
+#[derive(Debug, Clone)]
+struct FileDecision {
+    target_language: String,
+    prompt_template: String,
+    engine: Option<String>,
+}
 
+impl Default for FileDecision {
+    fn default() -> Self {
+        FileDecision {
+            target_language: "English".to_string(),
+            prompt_template:
+                "translate the content to {language}: please just reply with the translated content\n{content}"
+                    .to_string(),
+            engine: None,
+        }
+    }
+}
+
+/// Which files (relative to the repo root) get translated, expressed as glob patterns such as
+/// `docs/**/*.md`, and which engine (by name, from `TranslationConfig.engines`) translates them.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 struct Filter {
-    target: String,
-    include: Vec<String>,
-    exclude: Vec<String>,
+    #[serde(flatten)]
+    selection: Selection,
+    /// Name of the engine used for files this filter selects, unless a script decision
+    /// overrides it. Defaults to the first engine in `TranslationConfig.engines`.
+    #[serde(default)]
+    engine: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+enum Selection {
+    /// Translate files matching one of `only`, minus any also matching `except` - so an
+    /// allowlist can still carve out exclusions instead of being all-or-nothing.
+    Only {
+        only: Vec<String>,
+        #[serde(default)]
+        except: Vec<String>,
+    },
+    /// Translate every discovered file except those matching one of `except`.
+    Except { except: Vec<String> },
+}
+
+/// A `Filter` compiled into matchers: a file is included if it matches `only` (or there's no
+/// `only`, meaning everything is a candidate) and does *not* match `except`.
+struct CompiledFilter {
+    only: Option<globset::GlobSet>,
+    except: globset::GlobSet,
+}
+
+impl CompiledFilter {
+    fn is_match(&self, rel: &str) -> bool {
+        let selected = match &self.only {
+            Some(only) => only.is_match(rel),
+            None => true,
+        };
+        selected && !self.except.is_match(rel)
+    }
+}
+
+fn build_glob_set(patterns: &[String]) -> Result<globset::GlobSet, String> {
+    let mut builder = globset::GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = globset::Glob::new(pattern)
+            .map_err(|e| format!("invalid glob pattern {}: {}", pattern, e))?;
+        builder.add(glob);
+    }
+    builder
+        .build()
+        .map_err(|e| format!("failed to build glob matcher: {}", e))
+}
+
+impl Filter {
+    /// Compile this filter's patterns into a matcher.
+    fn compile(&self) -> Result<CompiledFilter, String> {
+        let (only, except) = match &self.selection {
+            Selection::Only { only, except } => (Some(build_glob_set(only)?), except),
+            Selection::Except { except } => (None, except),
+        };
+        let except = build_glob_set(except)?;
+        Ok(CompiledFilter { only, except })
+    }
+}
+
+/// Where to get the files to translate from.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Source {
+    /// A git repository, optionally pinned to a specific revision and restricted to a
+    /// `subpath` within the checkout.
+    Git {
+        remote: String,
+        branch: String,
+        #[serde(default)]
+        rev: Option<String>,
+        #[serde(default)]
+        subpath: Option<String>,
+    },
+    /// A plain local directory, already present on disk, with no VCS operations involved.
+    Local { path: String },
+}
+
+impl Source {
+    /// The name used to derive the workspace directories and metadata file for this source.
+    fn name(&self) -> String {
+        match self {
+            Source::Git { remote, .. } => {
+                let name = remote.split("/").last().unwrap();
+                name.split(".").next().unwrap().to_string()
+            }
+            Source::Local { path } => path
+                .trim_end_matches('/')
+                .split("/")
+                .last()
+                .unwrap()
+                .to_string(),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -27,14 +150,38 @@ struct Engine {
     url: String,
     model: String,
     api_key_file: String,
+    /// Maximum number of files this engine translates concurrently. Defaults to 1 (sequential)
+    /// when unset.
+    #[serde(default = "default_concurrency")]
+    concurrency: usize,
+}
+
+fn default_concurrency() -> usize {
+    1
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 struct TranslationConfig {
-    repo: String,
-    branch: String,
-    engine: Engine,
+    source: Source,
+    /// Engines tried for a file, in order: the one named by its filter or script decision
+    /// first (if any), falling back through the rest on failure.
+    engines: Vec<Engine>,
     filter: Filter,
+    /// Path to a Rhai script whose `decide(path, preview)` picks the target language, prompt,
+    /// and engine per file. Requires the `rhai` feature; ignored otherwise.
+    #[serde(default)]
+    script: Option<String>,
+}
+
+/// Order `engines` for a single file: the one named by `preferred` first (if it exists in the
+/// list), then the rest in their configured order as fallbacks.
+fn engine_order<'a>(engines: &'a [Engine], preferred: Option<&str>) -> Vec<&'a Engine> {
+    let mut ordered: Vec<&Engine> = Vec::with_capacity(engines.len());
+    if let Some(name) = preferred {
+        ordered.extend(engines.iter().filter(|e| e.name == name));
+    }
+    ordered.extend(engines.iter().filter(|e| Some(e.name.as_str()) != preferred));
+    ordered
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -42,12 +189,63 @@ struct FileEntry {
     path: String,
     hash: String,
     translation_timestamp: u64,
+    /// Name of the engine that produced this translation. A re-run retranslates the file when
+    /// this engine is no longer configured; a change of preferred engine alone does not.
+    engine: String,
+}
+
+/// What happened to a single file handed to the translation pool, reported back to the
+/// collector task over the results channel.
+enum FileOutcome {
+    /// Already translated by a still-configured engine, or empty; nothing to translate.
+    Skipped,
+    /// Freshly translated by `engine`.
+    Translated { engine: String },
+    /// Every engine failed; the original content was copied through untranslated so the
+    /// `-translated` tree still has a file at this path.
+    Failed,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct TranslationMeta {
     commit: String,
     files: Vec<FileEntry>,
+    /// Commit each submodule was pinned to as of this sync. Per-file content hashing is what
+    /// actually decides whether a file needs retranslating; this is kept only so the next run
+    /// can report which submodules moved since the last sync.
+    #[serde(default)]
+    submodules: Vec<backend::SubmoduleStatus>,
+}
+
+#[derive(Debug, Serialize)]
+struct ChatMessage {
+    role: &'static str,
+    content: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ChatRequest {
+    model: String,
+    temperature: f32,
+    top_p: f32,
+    n: u32,
+    stream: bool,
+    messages: Vec<ChatMessage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatChoice {
+    message: ChatResponseMessage,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatResponseMessage {
+    content: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatResponse {
+    choices: Vec<ChatChoice>,
 }
 
 fn cli() -> Command {
@@ -61,55 +259,106 @@ fn cli() -> Command {
         )
 }
 
-/// Parse the target filter string into a list of suffixes.
-/// For example, "*.md *.txt" will be parsed into ["md", "txt"].
-fn prase_target_suffix(target: &str) -> Vec<String> {
-    // "*.md *.txt" -> "md txt"
-    let mut suffix = target.replace("*", "");
-    suffix = suffix.replace(".", "");
-    let r = suffix.split(" ").map(|s| s.to_string()).collect();
-    println!("Suffix: {:?}", r);
-    r
+/// Maximum attempts made against a single engine before falling back to the next one.
+const MAX_ATTEMPTS_PER_ENGINE: u32 = 3;
+
+/// Translate `content` by trying `engines` in order, retrying each with exponential backoff
+/// before falling back to the next. Returns the translated content and the name of the engine
+/// that produced it, so the caller can record which engine a file was translated with.
+/// `engine_limits` gates each engine by its own `concurrency`, separately from the pool that
+/// bounds how many files are in flight overall.
+async fn agent_translate(
+    client: &reqwest::Client,
+    content: &str,
+    engines: &[&Engine],
+    decision: &FileDecision,
+    engine_limits: &std::collections::HashMap<String, Arc<tokio::sync::Semaphore>>,
+) -> Result<(String, String), String> {
+    let mut last_err = String::new();
+    for engine in engines {
+        let _permit = engine_limits
+            .get(&engine.name)
+            .expect("every configured engine has a semaphore")
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|e| format!("engine {} semaphore closed: {}", engine.name, e))?;
+        for attempt in 0..MAX_ATTEMPTS_PER_ENGINE {
+            match translate_once(client, content, engine, decision).await {
+                Ok(translated) => return Ok((translated, engine.name.clone())),
+                Err(e) => {
+                    last_err = format!("engine {}: {}", engine.name, e);
+                    if attempt + 1 < MAX_ATTEMPTS_PER_ENGINE {
+                        let backoff = std::time::Duration::from_millis(200 * 2u64.pow(attempt));
+                        println!(
+                            "{} (attempt {}/{}), retrying in {:?}...",
+                            last_err,
+                            attempt + 1,
+                            MAX_ATTEMPTS_PER_ENGINE,
+                            backoff
+                        );
+                        tokio::time::sleep(backoff).await;
+                    }
+                }
+            }
+        }
+        println!(
+            "Engine {} exhausted retries, falling back to the next engine",
+            engine.name
+        );
+    }
+    Err(format!("all engines failed; last error: {}", last_err))
 }
 
-/// Translate the content using the specified translation engine.
-fn agent_translate(content: String, config: &TranslationConfig) -> String {
-    let engine = &config.engine;
-    let url = &engine.url;
-    let model = &engine.model;
-    let query = format!(
-        "translate the content to English: please just reply with the translated content\n{}",
-        content
-    );
-    let key = std::fs::read_to_string(&engine.api_key_file);
-    let auth = Auth::new(key.unwrap().trim());
-    let agent = OpenAI::new(auth, url);
-
-    let body = ChatBody {
-        model: model.to_string(),
-        max_tokens: None,
-        temperature: Some(0.7),
-        top_p: Some(0.7),
-        n: Some(1),
-        stream: Some(false),
-        stop: None,
-        presence_penalty: None,
-        frequency_penalty: None,
-        logit_bias: None,
-        user: None,
-        messages: vec![Message {
-            role: Role::User,
+/// Make a single translation request against one engine, following `decision` for the target
+/// language and prompt.
+async fn translate_once(
+    client: &reqwest::Client,
+    content: &str,
+    engine: &Engine,
+    decision: &FileDecision,
+) -> Result<String, String> {
+    let key = std::fs::read_to_string(&engine.api_key_file)
+        .map_err(|e| format!("failed to read api key file {}: {}", engine.api_key_file, e))?;
+    let query = decision
+        .prompt_template
+        .replace("{language}", &decision.target_language)
+        .replace("{content}", content);
+    let body = ChatRequest {
+        model: engine.model.clone(),
+        temperature: 0.7,
+        top_p: 0.7,
+        n: 1,
+        stream: false,
+        messages: vec![ChatMessage {
+            role: "user",
             content: query,
         }],
     };
-    let rs = agent.chat_completion_create(&body);
-    let choice = rs.unwrap().choices;
-    let message = &choice[0].message.as_ref().unwrap();
-    message.content.clone()
+    let resp = client
+        .post(&engine.url)
+        .bearer_auth(key.trim())
+        .json(&body)
+        .send()
+        .await
+        .map_err(|e| format!("request to {} failed: {}", engine.url, e))?;
+    let resp = resp
+        .error_for_status()
+        .map_err(|e| format!("engine {} returned an error: {}", engine.name, e))?;
+    let parsed: ChatResponse = resp
+        .json()
+        .await
+        .map_err(|e| format!("failed to parse response from {}: {}", engine.name, e))?;
+    let choice = parsed
+        .choices
+        .into_iter()
+        .next()
+        .ok_or_else(|| format!("engine {} returned no choices", engine.name))?;
+    Ok(choice.message.content)
 }
 
 /// The main function to run the auto-translation process.
-fn run(config: TranslationConfig) {
+async fn run(config: TranslationConfig) {
     println!("Running the auto-translation with the following config:");
     println!("{:?}", config);
     // step1: clone the repo into workspace, default at ./workspace
@@ -118,17 +367,14 @@ fn run(config: TranslationConfig) {
         println!("Creating workspace folder at ./workspace");
         std::fs::create_dir("./workspace").unwrap();
     }
-    // clone the repo
+    // sync the source into the workspace
     // the translated snapshots will be places under ./workspace/<repo_name>-translated
-    let repo = &config.repo;
-    let branch = &config.branch;
     let workspace = "./workspace";
-    let repo_name = repo.split("/").last().unwrap();
-    let repo_name = repo_name.split(".").next().unwrap();
-    let repo_path = format!("{}/{}", workspace, repo_name);
-    let translated_repo_path = format!("{}/{}-translated", workspace, repo_name);
+    let source_name = config.source.name();
+    let repo_path = format!("{}/{}", workspace, source_name);
+    let translated_repo_path = format!("{}/{}-translated", workspace, source_name);
     // translation metadata stored in the {workspace}/{repo_name}.meta.json
-    let meta_path = format!("{}/{}.meta.json", workspace, repo_name);
+    let meta_path = format!("{}/{}.meta.json", workspace, source_name);
     // we need to implement the "SYNC" logic:
     // 1. if {repo_name} folder exists, pull the latest changes
     // 2. else clone the repo
@@ -139,28 +385,52 @@ fn run(config: TranslationConfig) {
     //      - we translate the file content and write to the translated_repo_path
     // 5. update metadata file with the latest commit hash, all relative paths according to the workspace root that are translated, we store a SHA256 hash of the original file content, sync timestamp, etc.
 
-    if std::path::Path::new(&repo_path).exists() {
-        println!("Pulling the latest changes from the repo: {}", repo);
-        let output = std::process::Command::new("git")
-            .arg("pull")
-            .current_dir(&repo_path)
-            .output()
-            .expect("Failed to pull the latest changes from the repo");
-        println!("{}", String::from_utf8_lossy(&output.stdout));
-        println!("{}", String::from_utf8_lossy(&output.stderr));
-    } else {
-        println!("Cloning the repo: {}", repo);
-        let output = std::process::Command::new("git")
-            .arg("clone")
-            .arg("--branch")
-            .arg(branch)
-            .arg(repo)
-            .arg(repo_path.clone())
-            .output()
-            .expect("Failed to clone the repo");
-        println!("{}", String::from_utf8_lossy(&output.stdout));
-        println!("{}", String::from_utf8_lossy(&output.stderr));
-    }
+    // the root to walk when discovering files; for a git source restricted to a `subpath`,
+    // this is a subdirectory of `repo_path`, and only files under it are translated.
+    let scan_root = match &config.source {
+        Source::Git {
+            remote,
+            branch,
+            rev,
+            subpath,
+        } => {
+            let backend = GitBackend;
+            if let Err(e) = backend.sync(remote, branch, &repo_path) {
+                println!("{}", e);
+                return;
+            }
+            if let Some(rev) = rev {
+                if let Err(e) = backend.checkout(&repo_path, rev) {
+                    println!("{}", e);
+                    return;
+                }
+            }
+            // pick up submodules whether this was a fresh clone or a pull of a repo that
+            // just gained one
+            if let Err(e) = backend.sync_submodules(&repo_path) {
+                println!("{}", e);
+                return;
+            }
+            match subpath {
+                Some(subpath) => format!("{}/{}", repo_path, subpath),
+                None => repo_path.clone(),
+            }
+        }
+        Source::Local { path } => {
+            if !std::path::Path::new(path).exists() {
+                println!("Local source path does not exist: {}", path);
+                return;
+            }
+            path.clone()
+        }
+    };
+
+    // the root filter patterns are relative to: always the repo root, even when `scan_root` is
+    // narrowed to a `subpath` - so `docs/**/*.md` means the same thing regardless of subpath
+    let filter_root = match &config.source {
+        Source::Git { .. } => repo_path.clone(),
+        Source::Local { path } => path.clone(),
+    };
 
     // read the metadata file
     let mut meta = if std::path::Path::new(&meta_path).exists() {
@@ -181,28 +451,62 @@ fn run(config: TranslationConfig) {
         TranslationMeta {
             commit: "".to_string(),
             files: vec![],
+            submodules: vec![],
         }
     };
 
-    // update comit hash
-    let output = std::process::Command::new("git")
-        .arg("rev-parse")
-        .arg("HEAD")
-        .current_dir(&repo_path)
-        .output()
-        .expect("Failed to get the latest commit hash");
-    let commit = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    // update the recorded revision and submodule pins; a local source has no VCS to pin, so
+    // both are left empty.
+    let (commit, submodules) = match &config.source {
+        Source::Git { .. } => {
+            let backend = GitBackend;
+            let commit = match backend.current_commit(&repo_path) {
+                Ok(commit) => commit,
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            };
+            let submodules = match backend.submodule_status(&repo_path) {
+                Ok(submodules) => submodules,
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            };
+            (commit, submodules)
+        }
+        Source::Local { .. } => (String::new(), vec![]),
+    };
     meta.commit = commit.clone();
 
+    // consult the previously recorded pins to report which submodules moved since the last
+    // sync - the file content hashes below are what actually drive retranslation
+    let previous_submodule_pins: std::collections::HashMap<String, String> = meta
+        .submodules
+        .iter()
+        .map(|s| (s.path.clone(), s.commit.clone()))
+        .collect();
+    for submodule in &submodules {
+        match previous_submodule_pins.get(&submodule.path) {
+            Some(prev) if prev != &submodule.commit => println!(
+                "Submodule {} moved from {} to {} since the last sync",
+                submodule.path, prev, submodule.commit
+            ),
+            None => println!("Submodule {} is new since the last sync", submodule.path),
+            _ => {}
+        }
+    }
+    meta.submodules = submodules;
+
     println!("Latest commit hash: {}", commit);
 
     // println!("Metadata: {:?}", meta);
 
-    // iterate all files in the repo
-    let mut q = vec![repo_path.clone()];
+    // iterate all files in the repo, starting from the (possibly subpath-restricted) scan root
+    let mut q = vec![scan_root.clone()];
     let mut files = vec![];
-    while !q.is_empty() {
-        let path = q.pop().unwrap();
+    while let Some(path) = q.pop() {
         let entries = std::fs::read_dir(&path);
         if let Err(e) = entries {
             println!("Error reading the directory @ {}: {}", path, e);
@@ -211,11 +515,14 @@ fn run(config: TranslationConfig) {
         for entry in entries.unwrap() {
             let entry = entry.unwrap();
             let path = entry.path();
+            // skip every nested `.git` - a directory in a normal checkout, but a plain file
+            // (the gitlink back to the superproject's `.git/modules/...`) at a submodule root
+            if path.ends_with(".git") {
+                continue;
+            }
             if path.is_dir() {
-                // skip the .git folder
-                if path.ends_with(".git") {
-                    continue;
-                }
+                // this recurses into submodule working trees too, since `sync_submodules`
+                // checks them out as regular directories on disk
                 q.push(path.to_str().unwrap().to_string());
             } else {
                 let path = path.to_str().unwrap().to_string();
@@ -224,105 +531,233 @@ fn run(config: TranslationConfig) {
         }
     }
     // println!("Files: {:?}", files);
-    // filter the files
-    let filter = &config.filter;
-    let suffix = prase_target_suffix(&filter.target);
+    // filter the files using the compiled glob matcher, matching against each file's path
+    // relative to the repo root so patterns like `docs/**/*.md` behave as users expect
+    // regardless of whether a `subpath` narrowed the scan root
+    let matcher = match config.filter.compile() {
+        Ok(compiled) => compiled,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
     let mut filtered_files = vec![];
     for file in &files {
-        // support suffix filter for now
-        let mut include = false;
-        for s in &suffix {
-            if file.ends_with(s) {
-                include = true;
-                break;
-            }
-        }
-        if !include {
-            continue;
+        let rel = file
+            .strip_prefix(&filter_root)
+            .unwrap_or(file)
+            .trim_start_matches('/');
+        if matcher.is_match(rel) {
+            filtered_files.push(file.clone());
         }
-        let mut exclude = false;
-        for e in &filter.exclude {
-            if file.contains(e) {
-                exclude = true;
-                break;
-            }
-        }
-        if exclude {
-            continue;
-        }
-        filtered_files.push(file.clone());
     }
     // println!("Filtered files: {:?}", filtered_files);
     // first copy all files that not need to be translated(not in filtered_files)
     for file in &files {
-        if !filtered_files.contains(&file) {
-            let translated_path = file.replace(&repo_path, &translated_repo_path);
+        if !filtered_files.contains(file) {
+            let translated_path = file.replace(&scan_root, &translated_repo_path);
             let translated_dir = translated_path.rsplitn(2, "/").last().unwrap();
             if !std::path::Path::new(&translated_dir).exists() {
-                std::fs::create_dir_all(&translated_dir).unwrap();
+                std::fs::create_dir_all(translated_dir).unwrap();
             }
             // read binary and write binary
-            let content = std::fs::read(&file).unwrap();
+            let content = std::fs::read(file).unwrap();
             std::fs::write(&translated_path, content).unwrap();
         }
     }
     println!("Got {} files to translate", filtered_files.len());
-    // update the metadata file
-    let mut translated_count = 0;
-    for f in &filtered_files {
-        let hash = format!("{:x}", sha2::Sha256::digest(&std::fs::read(&f).unwrap()));
-        let mut translated = false;
-        for file in &meta.files {
-            if file.path == *f && file.hash == *hash {
-                translated = true;
-                break;
+
+    // if a routing script is configured, compile it once and evaluate `decide()` per file up
+    // front, before any file is handed to the concurrent translation pool below.
+    #[cfg(feature = "rhai")]
+    let routing_script = match &config.script {
+        Some(path) => match script::Script::load(path) {
+            Ok(s) => Some(s),
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        },
+        None => None,
+    };
+    let decisions: Vec<FileDecision> = filtered_files
+        .iter()
+        .map(|f| {
+            #[cfg(feature = "rhai")]
+            if let Some(script) = &routing_script {
+                let preview: String = std::fs::read_to_string(f)
+                    .unwrap_or_default()
+                    .chars()
+                    .take(512)
+                    .collect();
+                return match script.decide(f, &preview) {
+                    Ok(decision) => decision,
+                    Err(e) => {
+                        println!("{}; using default routing for {}", e, f);
+                        FileDecision::default()
+                    }
+                };
+            }
+            #[cfg(not(feature = "rhai"))]
+            let _ = f;
+            FileDecision::default()
+        })
+        .collect();
+
+    // translate the files with bounded concurrency. Each worker only does IO and the network
+    // round-trip, then sends its outcome over a channel to the collector task below, which is
+    // the sole owner of `meta`, builds the `FileEntry` and flushes it after every newly
+    // translated file *as translation of the rest continues* - so a crash mid-run still leaves
+    // a consistent `.meta.json`.
+    let client = reqwest::Client::new();
+    let engines = Arc::new(config.engines.clone());
+    let filter_engine = config.filter.engine.clone();
+    let scan_root = Arc::new(scan_root);
+    let translated_repo_path = Arc::new(translated_repo_path);
+    // bound the pool by the total concurrency all configured engines allow for; each engine's
+    // own limit is enforced separately by the semaphores below
+    let concurrency = engines.iter().map(|e| e.concurrency.max(1)).sum::<usize>().max(1);
+    let engine_limits: Arc<std::collections::HashMap<String, Arc<tokio::sync::Semaphore>>> =
+        Arc::new(
+            engines
+                .iter()
+                .map(|e| {
+                    (
+                        e.name.clone(),
+                        Arc::new(tokio::sync::Semaphore::new(e.concurrency.max(1))),
+                    )
+                })
+                .collect(),
+        );
+    let already_translated: std::collections::HashMap<(String, String), String> = meta
+        .files
+        .iter()
+        .map(|f| ((f.path.clone(), f.hash.clone()), f.engine.clone()))
+        .collect();
+
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<(String, String, FileOutcome)>(
+        concurrency.max(1) * 2,
+    );
+    let meta_path_for_collector = meta_path.clone();
+    let collector = tokio::spawn(async move {
+        let mut meta = meta;
+        let mut already_up_to_date = 0;
+        let mut newly_translated = 0;
+        let mut failed_files = vec![];
+        while let Some((path, hash, outcome)) = rx.recv().await {
+            match outcome {
+                FileOutcome::Skipped => already_up_to_date += 1,
+                FileOutcome::Translated { engine } => {
+                    let file_entry = FileEntry {
+                        path,
+                        hash,
+                        translation_timestamp: std::time::SystemTime::now()
+                            .duration_since(std::time::UNIX_EPOCH)
+                            .unwrap()
+                            .as_secs(),
+                        engine,
+                    };
+                    meta.files.push(file_entry);
+                    write_meta(&meta, &meta_path_for_collector);
+                    newly_translated += 1;
+                }
+                FileOutcome::Failed => failed_files.push(path),
             }
         }
-        if translated {
-            translated_count += 1;
-            continue;
-        }
-        let translated_path = f.replace(&repo_path, &translated_repo_path);
-        let translated_dir = translated_path.rsplitn(2, "/").last().unwrap();
-        if !std::path::Path::new(&translated_dir).exists() {
-            std::fs::create_dir_all(&translated_dir).unwrap();
-        }
-        let content = std::fs::read_to_string(&f).unwrap();
-        // if content is empty, just copy the file
-        if content.is_empty() {
-            std::fs::write(&translated_path, content).unwrap();
-            translated_count += 1;
-            continue;
-        }
-        print!("Translating file {}...", filename(f));
-        std::io::stdout().flush().unwrap();
-        // translate the content
-        let translated_content = agent_translate(content, &config);
-        std::fs::write(&translated_path, translated_content).unwrap();
-        let file_entry = FileEntry {
-            path: f.clone(),
-            hash,
-            translation_timestamp: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs(),
-        };
-        meta.files.push(file_entry);
-        write_meta(&meta, &meta_path);
-        println!("done");
-    }
+        (meta, already_up_to_date, newly_translated, failed_files)
+    });
+
+    stream::iter(filtered_files.iter().cloned().zip(decisions))
+        .for_each_concurrent(concurrency, |(f, decision)| {
+            let client = client.clone();
+            let engines = Arc::clone(&engines);
+            let filter_engine = filter_engine.clone();
+            let scan_root = Arc::clone(&scan_root);
+            let translated_repo_path = Arc::clone(&translated_repo_path);
+            let engine_limits = Arc::clone(&engine_limits);
+            let already_translated = already_translated.clone();
+            let tx = tx.clone();
+            async move {
+                let hash = format!("{:x}", sha2::Sha256::digest(std::fs::read(&f).unwrap()));
+
+                let translated_path = f.replace(scan_root.as_str(), translated_repo_path.as_str());
+                let translated_dir = translated_path.rsplitn(2, "/").last().unwrap();
+                if !std::path::Path::new(&translated_dir).exists() {
+                    std::fs::create_dir_all(translated_dir).unwrap();
+                }
+
+                let preferred = decision.engine.clone().or_else(|| filter_engine.clone());
+                let ordered = engine_order(&engines, preferred.as_deref());
+
+                // skip only if this exact file content was already translated by an engine
+                // still reachable from this file's ordered fallback set - comparing against
+                // just the preferred engine would force a retranslation every run whenever the
+                // preferred engine is down and a fallback keeps producing the translation
+                let already_ok = already_translated
+                    .get(&(f.clone(), hash.clone()))
+                    .is_some_and(|recorded| ordered.iter().any(|e| &e.name == recorded));
+                if already_ok {
+                    let _ = tx.send((f, hash, FileOutcome::Skipped)).await;
+                    return;
+                }
+
+                let content = std::fs::read_to_string(&f).unwrap();
+                // if content is empty, just copy the file
+                if content.is_empty() {
+                    std::fs::write(&translated_path, content).unwrap();
+                    let _ = tx.send((f, hash, FileOutcome::Skipped)).await;
+                    return;
+                }
+                println!("Translating file {}...", filename(&f));
+                let outcome = match agent_translate(&client, &content, &ordered, &decision, &engine_limits)
+                    .await
+                {
+                    Ok((translated_content, engine)) => {
+                        std::fs::write(&translated_path, translated_content).unwrap();
+                        println!("Translated file {}: done (engine: {})", filename(&f), engine);
+                        FileOutcome::Translated { engine }
+                    }
+                    Err(e) => {
+                        println!(
+                            "Failed to translate file {} (all engines failed: {}), copying the original instead",
+                            filename(&f),
+                            e
+                        );
+                        std::fs::write(&translated_path, content).unwrap();
+                        FileOutcome::Failed
+                    }
+                };
+                let _ = tx.send((f, hash, outcome)).await;
+            }
+        })
+        .await;
+    drop(tx);
+
+    let (meta, already_up_to_date, newly_translated, failed_files) = collector.await.unwrap();
+    // record this sync's commit and submodule pins even if no file needed translating
+    write_meta(&meta, &meta_path);
     println!(
-        "Translation finished, new files translated: {}, total files translated: {}, already translated files: {}",
-        filtered_files.len() - translated_count,
-        filtered_files.len(),
-        translated_count
+        "Translation finished: {} newly translated, {} already up to date, {} failed out of {} files",
+        newly_translated,
+        already_up_to_date,
+        failed_files.len(),
+        filtered_files.len()
     );
+    if !failed_files.is_empty() {
+        println!(
+            "Warning: the following files failed to translate and were copied untranslated into {}:",
+            translated_repo_path
+        );
+        for path in &failed_files {
+            println!("  {}", path);
+        }
+    }
 }
 
 /// Get the filename from the path.
 /// For example, "/path/to/file.txt" will return "file.txt".
 fn filename(path: &str) -> String {
-    path.rsplitn(2, "/").next().unwrap().to_string()
+    path.rsplit("/").next().unwrap().to_string()
 }
 
 /// Write the metadata to the metadata file.
@@ -336,11 +771,11 @@ fn write_meta(meta: &TranslationMeta, meta_path: &str) {
     let res = std::fs::write(meta_path, meta);
     if let Err(e) = res {
         println!("Error writing the metadata file @ {}: {}", meta_path, e);
-        return;
     }
 }
 
-fn main() {
+#[tokio::main]
+async fn main() {
     let matches = cli().get_matches();
     match matches.subcommand() {
         Some(("run", run_matches)) => {
@@ -352,7 +787,7 @@ fn main() {
             }
             let config = serde_yaml::from_str(&config.unwrap());
             match config {
-                Ok(config) => run(config),
+                Ok(config) => run(config).await,
                 Err(e) => {
                     println!("Error parsing the config file: {}", e);
                 }
@@ -364,3 +799,80 @@ fn main() {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn engine(name: &str) -> Engine {
+        Engine {
+            name: name.to_string(),
+            url: String::new(),
+            model: String::new(),
+            api_key_file: String::new(),
+            concurrency: 1,
+        }
+    }
+
+    #[test]
+    fn engine_order_puts_preferred_first() {
+        let engines = vec![engine("a"), engine("b"), engine("c")];
+        let ordered = engine_order(&engines, Some("b"));
+        let names: Vec<&str> = ordered.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["b", "a", "c"]);
+    }
+
+    #[test]
+    fn engine_order_falls_back_to_configured_order_when_preferred_is_missing() {
+        let engines = vec![engine("a"), engine("b")];
+        let ordered = engine_order(&engines, Some("missing"));
+        let names: Vec<&str> = ordered.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn engine_order_with_no_preference_keeps_configured_order() {
+        let engines = vec![engine("a"), engine("b")];
+        let ordered = engine_order(&engines, None);
+        let names: Vec<&str> = ordered.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "b"]);
+    }
+
+    fn compile(selection: Selection) -> CompiledFilter {
+        Filter {
+            selection,
+            engine: None,
+        }
+        .compile()
+        .unwrap()
+    }
+
+    #[test]
+    fn only_filter_matches_allowed_patterns() {
+        let filter = compile(Selection::Only {
+            only: vec!["docs/**/*.md".to_string()],
+            except: vec![],
+        });
+        assert!(filter.is_match("docs/guide/intro.md"));
+        assert!(!filter.is_match("src/main.rs"));
+    }
+
+    #[test]
+    fn only_filter_carves_out_except() {
+        let filter = compile(Selection::Only {
+            only: vec!["docs/**/*.md".to_string()],
+            except: vec!["docs/internal/**".to_string()],
+        });
+        assert!(filter.is_match("docs/guide/intro.md"));
+        assert!(!filter.is_match("docs/internal/secret.md"));
+    }
+
+    #[test]
+    fn except_filter_matches_everything_but_except() {
+        let filter = compile(Selection::Except {
+            except: vec!["**/*.lock".to_string()],
+        });
+        assert!(filter.is_match("docs/guide/intro.md"));
+        assert!(!filter.is_match("Cargo.lock"));
+    }
+}
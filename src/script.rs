@@ -0,0 +1,66 @@
+//! Optional per-file translation routing via Rhai scripts.
+//!
+//! `agent_translate` otherwise hard-codes a single English prompt and engine for every file.
+//! When `TranslationConfig.script` points at a `.rhai` file, the tool compiles it once and
+//! evaluates its `decide(path, preview)` function per file, letting a user translate
+//! `README.*` to multiple languages, skip code blocks, or route API docs vs. prose to
+//! different engines without recompiling. Kept behind the `rhai` feature so the base build
+//! doesn't pay for the scripting engine.
+
+use crate::FileDecision;
+use rhai::{AST, Engine as RhaiEngine, Scope};
+
+/// A compiled routing script, loaded once and evaluated per file.
+pub struct Script {
+    engine: RhaiEngine,
+    ast: AST,
+}
+
+impl Script {
+    /// Compile the Rhai script at `path`.
+    pub fn load(path: &str) -> Result<Script, String> {
+        let engine = RhaiEngine::new();
+        let ast = engine
+            .compile_file(path.into())
+            .map_err(|e| format!("failed to compile script {}: {}", path, e))?;
+        Ok(Script { engine, ast })
+    }
+
+    /// Evaluate `decide(path, preview)` for a single file, returning the routing decision it
+    /// produced. `preview` is a content prefix, not the whole file, so large files stay cheap
+    /// to route.
+    pub fn decide(&self, path: &str, preview: &str) -> Result<FileDecision, String> {
+        let mut scope = Scope::new();
+        let result: rhai::Map = self
+            .engine
+            .call_fn(
+                &mut scope,
+                &self.ast,
+                "decide",
+                (path.to_string(), preview.to_string()),
+            )
+            .map_err(|e| format!("script evaluation failed for {}: {}", path, e))?;
+
+        let mut decision = FileDecision::default();
+        if let Some(v) = result.get("language") {
+            decision.target_language = v
+                .clone()
+                .into_string()
+                .map_err(|_| "decide(): `language` must be a string".to_string())?;
+        }
+        if let Some(v) = result.get("prompt") {
+            decision.prompt_template = v
+                .clone()
+                .into_string()
+                .map_err(|_| "decide(): `prompt` must be a string".to_string())?;
+        }
+        if let Some(v) = result.get("engine") {
+            decision.engine = Some(
+                v.clone()
+                    .into_string()
+                    .map_err(|_| "decide(): `engine` must be a string".to_string())?,
+            );
+        }
+        Ok(decision)
+    }
+}